@@ -0,0 +1,629 @@
+//! grup - a offline github markdown previewer.
+//!
+//! The rendering and serving logic lives here as a reusable [`Previewer`] so
+//! that editor plugins can drive grup programmatically (create one, push buffer
+//! contents with [`Previewer::set_content`], and call [`Previewer::listen`]),
+//! the same way `aurelius` exposes its `Server` object.
+
+#[macro_use]
+extern crate log;
+
+// md parser + formatter
+extern crate comrak;
+// simple http server
+extern crate simple_server;
+// websocket live-reload channel
+extern crate ws;
+// code-fence syntax highlighting
+extern crate syntect;
+
+use comrak::plugins::syntect::SyntectAdapter;
+use comrak::{ComrakOptions, ComrakPlugins};
+use simple_server::Server;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use inotify::{EventMask, Inotify, WatchMask};
+
+const DEFAULT_CSS: &[u8] = include_bytes!("../resource/github-markdown.css");
+
+const RELOAD_INTERVAL: u64 = 60;
+
+/// The rendering knobs threaded into every render call. GFM is on by default to
+/// match grup's stated purpose; flip the extension fields off to disable them.
+#[derive(Clone)]
+pub struct RenderConfig {
+    pub theme: String,
+    pub table: bool,
+    pub strikethrough: bool,
+    pub tasklist: bool,
+    pub autolink: bool,
+    pub footnotes: bool,
+    /// Largest file grup will read before bailing out with a notice.
+    pub max_size: u64,
+}
+
+/// 10 MiB — plenty for a README, small enough to keep `grup some-binary` safe.
+pub const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+impl Default for RenderConfig {
+    fn default() -> RenderConfig {
+        RenderConfig {
+            theme: String::from("InspiredGitHub"),
+            table: true,
+            strikethrough: true,
+            tasklist: true,
+            autolink: true,
+            footnotes: true,
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+}
+
+/// Why a file could not be previewed as markdown, borrowed from the ptth
+/// server's markdown module.
+enum MarkdownError {
+    /// The file is larger than the configured size limit (actual byte count).
+    TooBig(u64),
+    /// The bytes are not valid UTF-8, so we refuse to treat them as markdown.
+    NotUtf8,
+    /// The file could not be opened or read.
+    Io(std::io::Error),
+}
+
+/// Where a [`Previewer`] gets its markdown from.
+pub enum Source {
+    /// Watch and render a single markdown file.
+    File(PathBuf),
+    /// Browse and render a directory tree.
+    Directory(PathBuf),
+    /// Render markdown pushed in via [`Previewer::set_content`], a `POST
+    /// /content` request, or stdin — nothing is read from disk.
+    Pipe,
+}
+
+/// A running markdown preview: an HTTP server, a live-reload websocket, and
+/// (for disk sources) an inotify watcher, all sharing one reload channel.
+pub struct Previewer {
+    host: String,
+    port: u16,
+    ws_port: u16,
+    render_cfg: RenderConfig,
+    source: Source,
+    // flag kept for the long-poll fallback path
+    modified: Arc<AtomicBool>,
+    // one handle is enough: ws::Sender::broadcast reaches every client
+    broadcaster: Arc<Mutex<Option<ws::Sender>>>,
+    // the latest markdown pushed in over the pipe, if any
+    content: Arc<Mutex<Option<String>>>,
+}
+
+impl Previewer {
+    /// Create a previewer bound to `host:port` with `ws_port` for live-reload.
+    pub fn new(
+        host: String,
+        port: u16,
+        ws_port: u16,
+        render_cfg: RenderConfig,
+        source: Source,
+    ) -> Previewer {
+        Previewer {
+            host,
+            port,
+            ws_port,
+            render_cfg,
+            source,
+            modified: Arc::new(AtomicBool::new(false)),
+            broadcaster: Arc::new(Mutex::new(None)),
+            content: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Replace the previewed markdown and trigger a reload in every open tab.
+    /// Used by editor plugins streaming buffer contents without touching disk.
+    pub fn set_content(&self, markdown: String) {
+        *self.content.lock().unwrap() = Some(markdown);
+        notify(&self.modified, &self.broadcaster);
+    }
+
+    /// Start serving and block forever. Spawns the websocket server and, for
+    /// disk sources, the inotify watcher.
+    pub fn listen(&self) {
+        self.spawn_websocket();
+        self.spawn_watcher();
+
+        let render_cfg = self.render_cfg.clone();
+        let ws_port = self.ws_port;
+        let modified = self.modified.clone();
+        let broadcaster = self.broadcaster.clone();
+        let content = self.content.clone();
+        let source_kind = self.source.kind();
+        let static_root = self.source.static_root();
+        let (host, port) = (format!("{}", self.host), format!("{}", self.port));
+
+        let mut server = Server::new(move |request, mut response| {
+            info!("Request received. {} {}", request.method(), request.uri());
+
+            if request.uri().path() == "/update" {
+                for _i in 0..RELOAD_INTERVAL {
+                    if modified.compare_and_swap(true, false, Ordering::Relaxed) == true {
+                        return Ok(response.body("yes".as_bytes().to_vec())?);
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(1000));
+                }
+                return Ok(response.body("no".as_bytes().to_vec())?);
+            }
+
+            if request.uri().path() == "/style.css" {
+                return Ok(response.body(DEFAULT_CSS.to_vec())?);
+            }
+
+            // editors can push buffer contents without touching the filesystem
+            if request.method().as_str() == "POST" && request.uri().path() == "/content" {
+                let md = String::from_utf8_lossy(request.body()).into_owned();
+                *content.lock().unwrap() = Some(md);
+                notify(&modified, &broadcaster);
+                return Ok(response.body("ok".as_bytes().to_vec())?);
+            }
+
+            // pushed content always wins over whatever is on disk
+            if let Some(md) = content.lock().unwrap().as_ref() {
+                let body = render_string(md, &render_cfg);
+                let doc = wrap_document("grup", &body, ws_port);
+                return Ok(response.body(doc.into_bytes())?);
+            }
+
+            match &source_kind {
+                SourceKind::Directory(root) => {
+                    let rel_str = request.uri().path().trim_start_matches('/');
+                    let target = root.join(rel_str);
+
+                    if target.is_dir() {
+                        // Confirm the resolved directory stays under the tree
+                        // root before listing it, guarding against ../ escapes.
+                        if safe_join(root, rel_str).is_none() {
+                            response.status(404);
+                            return Ok(response.body(b"404 Not Found".to_vec())?);
+                        }
+                        let doc = render_index(root, Path::new(rel_str), ws_port);
+                        return Ok(response.body(doc.into_bytes())?);
+                    }
+
+                    if is_markdown(&target) {
+                        // Resolve inside the tree root before reading, so a
+                        // ../ path can't render a markdown file outside it.
+                        match safe_join(root, rel_str) {
+                            Some(target) => {
+                                let body = render_markdown(&target, &render_cfg);
+                                let doc =
+                                    wrap_document(&target.to_string_lossy(), &body, ws_port);
+                                return Ok(response.body(doc.into_bytes())?);
+                            }
+                            None => {
+                                response.status(404);
+                                return Ok(response.body(b"404 Not Found".to_vec())?);
+                            }
+                        }
+                    }
+
+                    // stream any other served asset, scoped to the tree root
+                    match read_asset(root, rel_str) {
+                        Some((bytes, ct)) => {
+                            response.header("content-type", ct);
+                            Ok(response.body(bytes)?)
+                        }
+                        None => {
+                            response.status(404);
+                            Ok(response.body(b"404 Not Found".to_vec())?)
+                        }
+                    }
+                }
+                SourceKind::File(path) => {
+                    let rel_str = request.uri().path().trim_start_matches('/');
+                    if rel_str.is_empty() {
+                        let body = render_markdown(path, &render_cfg);
+                        let title = path.to_str().unwrap_or("markdown").to_string();
+                        let doc = wrap_document(&title, &body, ws_port);
+                        return Ok(response.body(doc.into_bytes())?);
+                    }
+
+                    // an asset linked from the document, scoped to its directory
+                    match read_asset(&static_root, rel_str) {
+                        Some((bytes, ct)) => {
+                            response.header("content-type", ct);
+                            Ok(response.body(bytes)?)
+                        }
+                        None => {
+                            response.status(404);
+                            Ok(response.body(b"404 Not Found".to_vec())?)
+                        }
+                    }
+                }
+                SourceKind::Pipe => {
+                    // nothing pushed yet
+                    let doc = wrap_document("grup", "<p>Waiting for content…</p>", ws_port);
+                    Ok(response.body(doc.into_bytes())?)
+                }
+            }
+        });
+
+        // Scope the static root to the document's directory rather than the
+        // process CWD so relative images and assets resolve correctly.
+        server.set_static_directory(static_root.to_str().unwrap_or("."));
+
+        println!("Server running at http://{}:{}", host, port);
+        println!("Press Ctrl-C to exit");
+        server.listen(&host, &port);
+    }
+
+    fn spawn_websocket(&self) {
+        let host = format!("{}", self.host);
+        let ws_port = self.ws_port;
+        let broadcaster = self.broadcaster.clone();
+        std::thread::spawn(move || {
+            let socket = ws::Builder::new()
+                .build(move |out: ws::Sender| {
+                    *broadcaster.lock().unwrap() = Some(out);
+                    // clients never send us anything useful, just ignore it
+                    move |_msg| Ok(())
+                })
+                .expect("failed to build websocket server");
+            if let Err(e) = socket.listen((host.as_str(), ws_port)) {
+                error!("live-reload websocket failed: {:?}", e);
+            }
+        });
+    }
+
+    fn spawn_watcher(&self) {
+        let (path, serve_dir) = match &self.source {
+            Source::File(p) => (p.clone(), false),
+            Source::Directory(p) => (p.clone(), true),
+            // a pipe source has nothing on disk to watch
+            Source::Pipe => return,
+        };
+
+        let mut inotify = Inotify::init().expect("inotify init failed");
+        if serve_dir {
+            info!("serving directory {:?}", path);
+            // Watch the whole tree so edits to any served file trigger a reload.
+            watch_tree(&mut inotify, &path);
+        } else {
+            let parent = match path.parent() {
+                Some(parent) if parent.to_str() != Some("") => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            info!("parent {:?}", parent);
+            inotify
+                .add_watch(&parent, WatchMask::MODIFY | WatchMask::CREATE)
+                .expect("failed to watch");
+        }
+
+        let modified = self.modified.clone();
+        let broadcaster = self.broadcaster.clone();
+        std::thread::spawn(move || loop {
+            let mut buf = [0u8; 1024];
+            let events = inotify
+                .read_events_blocking(&mut buf)
+                .expect("failed to read events");
+            for event in events {
+                if event.mask.contains(EventMask::CREATE) {
+                    info!("file created {:?}", event.name.unwrap());
+                } else if event.mask.contains(EventMask::MODIFY) {
+                    info!("file modified {:?}", event.name.unwrap());
+                }
+                // In directory mode any modify under the tree is relevant; in
+                // single-file mode only the watched file counts. inotify reports
+                // a basename, so compare against the file name, not the full arg.
+                let relevant = serve_dir
+                    || path.file_name().and_then(|n| n.to_str()) == event.name.unwrap().to_str();
+                if relevant {
+                    notify(&modified, &broadcaster);
+                }
+            }
+        });
+    }
+}
+
+/// A cheap, `Send`-able view of a [`Source`]'s routing behaviour for the server
+/// closure (the closure can't hold the `Previewer` itself).
+enum SourceKind {
+    File(PathBuf),
+    Directory(PathBuf),
+    Pipe,
+}
+
+impl Source {
+    fn kind(&self) -> SourceKind {
+        match self {
+            Source::File(p) => SourceKind::File(p.clone()),
+            Source::Directory(p) => SourceKind::Directory(p.clone()),
+            Source::Pipe => SourceKind::Pipe,
+        }
+    }
+
+    /// The directory relative assets are resolved against.
+    fn static_root(&self) -> PathBuf {
+        match self {
+            Source::File(p) => match p.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            },
+            Source::Directory(p) => p.clone(),
+            Source::Pipe => PathBuf::from("."),
+        }
+    }
+}
+
+/// Resolve `rel` under `root`, returning the canonical path only if it stays
+/// inside `root`. This guards against `../` traversal outside the document's
+/// directory.
+fn safe_join(root: &Path, rel: &str) -> Option<PathBuf> {
+    let canon_root = std::fs::canonicalize(root).ok()?;
+    let canon_target = std::fs::canonicalize(canon_root.join(rel)).ok()?;
+    if canon_target.starts_with(&canon_root) {
+        Some(canon_target)
+    } else {
+        None
+    }
+}
+
+/// Read an asset scoped to `root`, returning its bytes and content type, or
+/// `None` if it is missing or would escape `root`.
+fn read_asset(root: &Path, rel: &str) -> Option<(Vec<u8>, &'static str)> {
+    let path = safe_join(root, rel)?;
+    let bytes = std::fs::read(&path).ok()?;
+    Some((bytes, content_type(&path)))
+}
+
+/// Flag a reload for the long-poll fallback and push a frame to live clients.
+fn notify(modified: &Arc<AtomicBool>, broadcaster: &Arc<Mutex<Option<ws::Sender>>>) {
+    modified.store(true, Ordering::Relaxed);
+    if let Some(out) = broadcaster.lock().unwrap().as_ref() {
+        if let Err(e) = out.broadcast("reload") {
+            error!("failed to push reload frame: {:?}", e);
+        }
+    }
+}
+
+/// Whether `path` should be rendered as markdown rather than streamed verbatim.
+fn is_markdown(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => {
+            let ext = ext.to_lowercase();
+            ext == "md" || ext == "markdown"
+        }
+        None => false,
+    }
+}
+
+/// Pick an emoji icon for a directory entry from its extension, mirroring the
+/// `get_file_type` helper in the `srv` file server.
+fn file_icon(path: &Path) -> &'static str {
+    if path.is_dir() {
+        return "\u{1F4C1}"; // folder
+    }
+    match path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase().as_str() {
+        "md" | "markdown" => "\u{1F4DD}",                                    // memo
+        "png" | "jpg" | "jpeg" | "gif" | "svg" | "bmp" | "webp" => "\u{1F5BC}", // framed picture
+        "rs" | "c" | "cpp" | "h" | "py" | "js" | "ts" | "go" | "java" | "rb" | "sh" => "\u{1F4BB}", // laptop
+        "zip" | "gz" | "tar" | "xz" | "bz2" | "7z" | "rar" => "\u{1F5DC}",   // compression
+        "pdf" => "\u{1F4D5}",                                                // closed book
+        "toml" | "yaml" | "yml" | "json" | "ini" | "cfg" => "\u{2699}",      // gear
+        _ => "\u{1F4C4}",                                                    // page
+    }
+}
+
+/// Map a file extension to a content type served with the bytes.
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "json" => "application/json",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "pdf" => "application/pdf",
+        "txt" | "md" | "markdown" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Turn a markdown string into an HTML fragment, applying the GFM extensions
+/// and syntect code highlighting selected in `cfg`.
+fn render_string(md: &str, cfg: &RenderConfig) -> String {
+    let mut options = ComrakOptions::default();
+    options.render.hardbreaks = true;
+    options.extension.table = cfg.table;
+    options.extension.strikethrough = cfg.strikethrough;
+    options.extension.tasklist = cfg.tasklist;
+    options.extension.autolink = cfg.autolink;
+    options.extension.footnotes = cfg.footnotes;
+
+    // Colourise fenced code blocks so they match the GitHub CSS theme.
+    let adapter = SyntectAdapter::new(&cfg.theme);
+    let mut plugins = ComrakPlugins::default();
+    plugins.render.codefence_syntax_highlighter = Some(&adapter);
+
+    comrak::markdown_to_html_with_plugins(md, &options, &plugins)
+}
+
+/// Read a markdown file, honouring the size limit and UTF-8 requirement.
+fn read_markdown(path: &Path, max_size: u64) -> Result<String, MarkdownError> {
+    let file = File::open(path).map_err(MarkdownError::Io)?;
+    if let Ok(meta) = file.metadata() {
+        if meta.len() > max_size {
+            return Err(MarkdownError::TooBig(meta.len()));
+        }
+    }
+    // Cap the reader too, in case the file grows past the limit between stat
+    // and read: read one extra byte and treat a full buffer as TooBig rather
+    // than silently rendering a truncated file.
+    let mut buf = Vec::new();
+    file.take(max_size + 1)
+        .read_to_end(&mut buf)
+        .map_err(MarkdownError::Io)?;
+    if buf.len() as u64 > max_size {
+        // the capped read stops at max_size + 1, so stat again for the real size
+        let size = std::fs::metadata(path)
+            .map(|m| m.len())
+            .unwrap_or(buf.len() as u64);
+        return Err(MarkdownError::TooBig(size));
+    }
+    String::from_utf8(buf).map_err(|_| MarkdownError::NotUtf8)
+}
+
+/// A clean, styled notice rendered in place of the markdown body.
+fn error_notice(message: &str) -> String {
+    format!("<h1>Cannot preview this file</h1>\n<p>{}</p>", message)
+}
+
+/// Read a markdown file off disk and turn it into an HTML fragment, falling
+/// back to a styled notice when the file is too big or not valid UTF-8.
+fn render_markdown(path: &Path, cfg: &RenderConfig) -> String {
+    match read_markdown(path, cfg.max_size) {
+        Ok(md) => render_string(&md, cfg),
+        Err(MarkdownError::TooBig(size)) => error_notice(&format!(
+            "This file is {} bytes, larger than the {} byte preview limit.",
+            size, cfg.max_size
+        )),
+        Err(MarkdownError::NotUtf8) => {
+            error_notice("This file is not valid UTF-8 text and cannot be previewed as markdown.")
+        }
+        Err(MarkdownError::Io(e)) => {
+            error_notice(&format!("This file could not be read: {}", e))
+        }
+    }
+}
+
+/// Render a browsable index listing for `root`/`rel` with file-type icons.
+fn render_index(root: &Path, rel: &Path, ws_port: u16) -> String {
+    let dir = root.join(rel);
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    if let Ok(read) = std::fs::read_dir(&dir) {
+        for entry in read.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if path.is_dir() {
+                dirs.push(name);
+            } else {
+                files.push(name);
+            }
+        }
+    }
+    dirs.sort();
+    files.sort();
+
+    let mut list = String::from("<ul style=\"list-style:none;padding-left:0\">\n");
+    if rel.parent().is_some() && rel != Path::new("") {
+        let parent = rel.parent().unwrap_or(Path::new(""));
+        list.push_str(&format!(
+            "<li>\u{2B06} <a href=\"/{}\">..</a></li>\n",
+            parent.to_string_lossy()
+        ));
+    }
+    for name in dirs.into_iter().chain(files) {
+        let entry_rel = rel.join(&name);
+        let entry_path = dir.join(&name);
+        list.push_str(&format!(
+            "<li>{} <a href=\"/{}\">{}</a></li>\n",
+            file_icon(&entry_path),
+            entry_rel.to_string_lossy(),
+            name
+        ));
+    }
+    list.push_str("</ul>");
+
+    let title = if rel == Path::new("") {
+        root.to_string_lossy().into_owned()
+    } else {
+        rel.to_string_lossy().into_owned()
+    };
+    wrap_document(&title, &list, ws_port)
+}
+
+/// Wrap an HTML body fragment in the github-markdown styled page, including the
+/// live-reload script (WebSocket with a long-poll fallback).
+fn wrap_document(title: &str, body: &str, ws_port: u16) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+             <html>
+                <head>
+                    <meta http-equiv="Content-Type" content="text/html; charset=utf-8"/>
+                    <style>
+                        body {{
+                        box-sizing: border-box;
+                        min-width: 200px;
+                        max-width: 980px;
+                        margin: 0 auto;
+                        padding: 45px;
+                        }}
+                    </style>
+                    <link rel="stylesheet" href="/style.css">
+                    <title>{title}</title>
+                </head>
+                <body>
+                <article class="markdown-body">
+                {body}
+                <article class="markdown-body">
+                <script type="text/javascript">
+                // Prefer an instant WebSocket push; if the upgrade fails for any
+                // reason fall back to the old long-poll against /update.
+                function start_polling () {{
+                    function reload_check () {{
+                        var xhr = new XMLHttpRequest();
+                        xhr.overrideMimeType("text/plain");
+                        xhr.onreadystatechange = function () {{
+                            if (this.status == 200) {{
+                                if (this.responseText == "yes") {{
+                                    location.reload();
+                                }}
+                            }}
+                        }}
+                        xhr.open("GET", "/update", true);
+                        xhr.send();
+                    }}
+                    reload_check();
+                    window.setInterval(reload_check, {interval});
+                }}
+                try {{
+                    var ws = new WebSocket("ws://" + window.location.hostname + ":{ws_port}/live");
+                    ws.onmessage = function () {{ location.reload(); }};
+                    ws.onerror = function () {{ ws.close(); }};
+                    ws.onclose = function () {{ start_polling(); }};
+                }} catch (e) {{
+                    start_polling();
+                }}
+                </script>
+                </body>
+            </html>"#,
+        title = title,
+        body = body,
+        interval = RELOAD_INTERVAL * 1000,
+        ws_port = ws_port
+    )
+}
+
+/// Recursively register inotify watches for `dir` and every subdirectory.
+fn watch_tree(inotify: &mut Inotify, dir: &Path) {
+    if inotify
+        .add_watch(dir, WatchMask::MODIFY | WatchMask::CREATE)
+        .is_err()
+    {
+        return;
+    }
+    if let Ok(read) = std::fs::read_dir(dir) {
+        for entry in read.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                watch_tree(inotify, &path);
+            }
+        }
+    }
+}